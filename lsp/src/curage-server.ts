@@ -20,6 +20,15 @@ import {
   TextEdit,
   InitializeParams,
   TextDocumentEdit,
+  Location,
+  CompletionItem,
+  CompletionItemKind,
+  SemanticTokens,
+  SemanticTokensLegend,
+  SemanticTokensParams,
+  DocumentSymbol,
+  DocumentSymbolParams,
+  SymbolKind,
 } from "vscode-languageserver-protocol"
 import {
   listenToLSPClient,
@@ -59,6 +68,21 @@ export const onMessage = (message: Message) => {
           // `textDocument/documentHighlight` requests.
           documentHighlightProvider: true,
           // Indicate that the server can respond to
+          // `textDocument/definition` requests.
+          definitionProvider: true,
+          // Indicate that the server can respond to
+          // `textDocument/completion` requests.
+          completionProvider: {},
+          // Indicate that the server can respond to
+          // `textDocument/semanticTokens/full` requests.
+          semanticTokensProvider: {
+            legend: semanticTokensLegend,
+            full: true,
+          },
+          // Indicate that the server can respond to
+          // `textDocument/documentSymbol` requests.
+          documentSymbolProvider: true,
+          // Indicate that the server can respond to
           // `textDocument/rename` requests;
           // and `textDocument/prepareRename` if the client supports.
           renameProvider:
@@ -104,6 +128,30 @@ export const onMessage = (message: Message) => {
       sendResponse(id, highlights || null)
       return
     }
+    case "textDocument/definition": {
+      const { textDocument: { uri }, position } = params as TextDocumentPositionParams
+      const location = createDefinitionLocation(uri, position)
+      sendResponse(id, location || null)
+      return
+    }
+    case "textDocument/completion": {
+      const { textDocument: { uri }, position } = params as TextDocumentPositionParams
+      const items = createCompletionItems(uri, position)
+      sendResponse(id, items || null)
+      return
+    }
+    case "textDocument/semanticTokens/full": {
+      const { textDocument: { uri } } = params as SemanticTokensParams
+      const semanticTokens = createSemanticTokens(uri)
+      sendResponse(id, semanticTokens || null)
+      return
+    }
+    case "textDocument/documentSymbol": {
+      const { textDocument: { uri } } = params as DocumentSymbolParams
+      const documentSymbols = createDocumentSymbols(uri)
+      sendResponse(id, documentSymbols || null)
+      return
+    }
     case "textDocument/prepareRename": {
       const { textDocument: { uri }, position } = params as TextDocumentPositionParams
       const result = prepareRename(uri, position)
@@ -153,6 +201,7 @@ interface LetStatement {
   type: "let",
   name: Token,
   init: Token,
+  range: Range,
 }
 
 type Statement =
@@ -176,13 +225,18 @@ interface SymbolDefinition {
   references: Token[],
 }
 
-/** Result of static analysis. */
-interface SemanticModel {
+/** Result of semantic analysis of statements. */
+interface AnalysisResult {
   statements: Statement[],
   symbolDefinitions: SymbolDefinition[],
   diagnostics: Diagnostic[],
 }
 
+/** Result of static analysis. */
+interface SemanticModel extends AnalysisResult {
+  tokens: Token[],
+}
+
 const comparePositions = (l: Position, r: Position) => {
   if (l.line !== r.line) {
     return Math.sign(l.line - r.line)
@@ -394,6 +448,8 @@ const parseTokens = (tokens: Token[]): SyntaxModel => {
   }
 
   const parseLetStatement = (): void => {
+    const { start } = tokens[i].range
+
     if (tokens[i].type !== "let") {
       return warn("Expected 'let'.")
     }
@@ -425,6 +481,7 @@ const parseTokens = (tokens: Token[]): SyntaxModel => {
       type: "let",
       name: nameToken,
       init: initToken,
+      range: { start, end: initToken.range.end },
     })
   }
 
@@ -498,7 +555,7 @@ export const testParseTokens = () => {
  * Performs semantic analysis statically
  * to make a mapping between tokens and symbols.
  */
-const analyzeStatements = (statements: Statement[]): SemanticModel => {
+const analyzeStatements = (statements: Statement[]): AnalysisResult => {
   const symbolDefinitions: SymbolDefinition[] = []
 
   // Map from names to defined symbols.
@@ -562,9 +619,11 @@ const analyzeStatements = (statements: Statement[]): SemanticModel => {
 }
 
 const analyzeSource = (source: string): SemanticModel => {
-  const { statements, diagnostics: d1 } = parseSource(source)
+  const tokens = tokenize(source)
+  const { statements, diagnostics: d1 } = parseTokens(tokens)
   const { symbolDefinitions, diagnostics: d2 } = analyzeStatements(statements)
   return {
+    tokens,
     statements,
     symbolDefinitions,
     diagnostics: [...d1, ...d2],
@@ -701,6 +760,395 @@ export const testHitTestSymbol = () => {
   }
 }
 
+/**
+ * Find the range of the definition-site
+ * of the symbol at the specified position.
+ */
+const findDefinitionRange = (semanticModel: SemanticModel, position: Position) => {
+  const hit = hitTestSymbol(semanticModel, position)
+  if (!hit) {
+    return undefined
+  }
+
+  return hit.symbolDefinition.definition.range
+}
+
+export const testFindDefinitionRange = () => {
+  const table = [
+    // Reference to definition.
+    {
+      source: "let x be 1\nlet y be x",
+      position: [1, 9],
+      expected: [[0, 4], [0, 5]],
+    },
+    // Shadowing case: refers to the nearest earlier definition.
+    {
+      source: "let x be 1\nlet x be x\nlet y be x",
+      position: [1, 9],
+      expected: [[0, 4], [0, 5]],
+    },
+    {
+      source: "let x be 1\nlet x be x\nlet y be x",
+      position: [2, 9],
+      expected: [[1, 4], [1, 5]],
+    },
+    // Keyword.
+    {
+      source: "let x be 1",
+      position: [0, 0],
+      expected: undefined,
+    },
+  ]
+
+  for (const { source, position: [line, character], expected } of table) {
+    const semanticModel = analyzeSource(source)
+    const range = findDefinitionRange(semanticModel, { line, character })
+    assert.deepStrictEqual(range && rangeToMatrix(range), expected)
+  }
+}
+
+/**
+ * Collect edits to rename the symbol at the specified position:
+ * the definition-site and all reference-sites.
+ */
+const collectRenameEdits = (semanticModel: SemanticModel, position: Position, newName: string): TextEdit[] => {
+  const hit = hitTestSymbol(semanticModel, position)
+  if (!hit) {
+    return []
+  }
+
+  const edits: TextEdit[] = []
+  const { definition, references } = hit.symbolDefinition
+
+  edits.push({
+    range: definition.range,
+    newText: newName,
+  })
+
+  for (const r of references) {
+    edits.push({
+      range: r.range,
+      newText: newName,
+    })
+  }
+
+  return edits
+}
+
+export const testCollectRenameEdits = () => {
+  const table = [
+    {
+      source: "let x be 1\nlet y be x",
+      position: [0, 4],
+      expected: [
+        [[0, 4], "z"],
+        [[1, 9], "z"],
+      ],
+    },
+    // Shadowing case: only the symbol at the position is renamed.
+    {
+      source: "let x be 1\nlet x be x\nlet y be x",
+      position: [1, 4],
+      expected: [
+        [[1, 4], "z"],
+        [[2, 9], "z"],
+      ],
+    },
+    // Keyword.
+    {
+      source: "let x be 1",
+      position: [0, 0],
+      expected: [],
+    },
+  ]
+
+  for (const { source, position: [line, character], expected } of table) {
+    const semanticModel = analyzeSource(source)
+    const edits = collectRenameEdits(semanticModel, { line, character }, "z")
+    const actual = edits.map(e => [positionToArray(e.range.start), e.newText])
+    assert.deepStrictEqual(actual, expected)
+  }
+}
+
+/**
+ * Collect candidates of completion at the specified position.
+ * What to offer depends on the token before the cursor in the line:
+ * `let` at the start of a line, `be` after a new name,
+ * and variables defined in lines before it after `be`.
+ */
+const collectCompletionItems = (semanticModel: SemanticModel, position: Position) => {
+  const items: CompletionItem[] = []
+
+  // Tokens in the line that start before the cursor.
+  const preceding = semanticModel.tokens.filter(token =>
+    token.range.start.line === position.line
+    && token.type !== "eol"
+    && token.range.start.character < position.character
+  )
+
+  // Exclude the word being typed at the cursor.
+  const last = preceding[preceding.length - 1]
+  if (last && last.range.end.character >= position.character) {
+    preceding.pop()
+  }
+
+  const prev = preceding[preceding.length - 1]
+  const prevPrev = preceding[preceding.length - 2]
+
+  if (!prev) {
+    items.push({
+      label: "let",
+      kind: CompletionItemKind.Keyword,
+    })
+    return items
+  }
+
+  if (prev.type === "name" && prevPrev && prevPrev.type === "let") {
+    items.push({
+      label: "be",
+      kind: CompletionItemKind.Keyword,
+    })
+    return items
+  }
+
+  if (prev.type !== "be") {
+    return items
+  }
+
+  // Names of variables defined in lines before the cursor.
+  const visible = new Set<string>()
+  for (const { definition } of semanticModel.symbolDefinitions) {
+    if (definition.range.start.line < position.line) {
+      visible.add(definition.value)
+    }
+  }
+
+  for (const name of visible) {
+    items.push({
+      label: name,
+      kind: CompletionItemKind.Variable,
+    })
+  }
+
+  return items
+}
+
+export const testCollectCompletionItems = () => {
+  const table = [
+    // After `be`: visible variables.
+    {
+      source: "let x be 1\nlet y be x\nlet x be ",
+      position: [2, 9],
+      expected: ["x", "y"],
+    },
+    {
+      source: "let x be 1\nlet y be x\n",
+      position: [1, 9],
+      expected: ["x"],
+    },
+    // While typing a name after `be`.
+    {
+      source: "let x be 1\nlet y be x\n",
+      position: [1, 10],
+      expected: ["x"],
+    },
+    // Shadowed names are offered once.
+    {
+      source: "let x be 1\nlet x be 2\nlet y be ",
+      position: [2, 9],
+      expected: ["x"],
+    },
+    // After `let`: a new name is expected.
+    {
+      source: "let x be 1\nlet ",
+      position: [1, 4],
+      expected: [],
+    },
+    // After a new name.
+    {
+      source: "let x be 1\nlet y ",
+      position: [1, 6],
+      expected: ["be"],
+    },
+    // At the start of a line.
+    {
+      source: "let x be 1\n",
+      position: [1, 0],
+      expected: ["let"],
+    },
+    {
+      source: "let x be 1\nle",
+      position: [1, 2],
+      expected: ["let"],
+    },
+    {
+      source: "",
+      position: [0, 0],
+      expected: ["let"],
+    },
+  ]
+
+  for (const { source, position: [line, character], expected } of table) {
+    const semanticModel = analyzeSource(source)
+    const items = collectCompletionItems(semanticModel, { line, character })
+    assert.deepStrictEqual(items.map(item => item.label), expected)
+  }
+}
+
+/**
+ * Kinds of semantic tokens the server reports.
+ * Indices of the arrays are used in the encoded data.
+ * `invalid` is a custom type for characters the tokenizer doesn't accept.
+ */
+const semanticTokensLegend: SemanticTokensLegend = {
+  tokenTypes: ["keyword", "number", "variable", "invalid"],
+  tokenModifiers: ["declaration"],
+}
+
+/**
+ * Classify tokens and encode them
+ * in the relative format of `SemanticTokens`.
+ */
+const encodeSemanticTokens = (semanticModel: SemanticModel): number[] => {
+  const { tokenTypes, tokenModifiers } = semanticTokensLegend
+
+  // Tokens that define a symbol.
+  const definitions = new Set<Token>()
+  for (const symbolDefinition of semanticModel.symbolDefinitions) {
+    definitions.add(symbolDefinition.definition)
+  }
+
+  const classify = (token: Token) => {
+    if (token.type === "let" || token.type === "be") {
+      return { tokenType: "keyword", modifiers: 0 }
+    }
+    if (token.type === "int") {
+      return { tokenType: "number", modifiers: 0 }
+    }
+    if (token.type === "name") {
+      const modifiers = definitions.has(token)
+        ? 1 << tokenModifiers.indexOf("declaration")
+        : 0
+      return { tokenType: "variable", modifiers }
+    }
+    if (token.type === "invalid") {
+      return { tokenType: "invalid", modifiers: 0 }
+    }
+    return undefined
+  }
+
+  const data: number[] = []
+
+  // Start position of the previous token.
+  let line = 0
+  let character = 0
+
+  for (const token of semanticModel.tokens) {
+    const kind = classify(token)
+    if (!kind) continue
+
+    const { start, end } = token.range
+    const deltaLine = start.line - line
+    const deltaStart = deltaLine === 0 ? start.character - character : start.character
+    const length = end.character - start.character
+
+    data.push(deltaLine, deltaStart, length, tokenTypes.indexOf(kind.tokenType), kind.modifiers)
+
+    line = start.line
+    character = start.character
+  }
+
+  return data
+}
+
+export const testEncodeSemanticTokens = () => {
+  const table = [
+    {
+      source: "let x be 1\nlet y be x",
+      expected: [
+        [0, 0, 3, 0, 0],
+        [0, 4, 1, 2, 1],
+        [0, 2, 2, 0, 0],
+        [0, 3, 1, 1, 0],
+        [1, 0, 3, 0, 0],
+        [0, 4, 1, 2, 1],
+        [0, 2, 2, 0, 0],
+        [0, 3, 1, 2, 0],
+      ],
+    },
+    // Invalid token.
+    {
+      source: "let x = 1",
+      expected: [
+        [0, 0, 3, 0, 0],
+        [0, 4, 1, 2, 0],
+        [0, 2, 1, 3, 0],
+        [0, 2, 1, 1, 0],
+      ],
+    },
+  ]
+
+  for (const { source, expected } of table) {
+    const data = encodeSemanticTokens(analyzeSource(source))
+    const actual: number[][] = []
+    for (let i = 0; i < data.length; i += 5) {
+      actual.push(data.slice(i, i + 5))
+    }
+    assert.deepStrictEqual(actual, expected)
+  }
+}
+
+/**
+ * Collect symbols defined by statements for outline.
+ */
+const collectDocumentSymbols = (semanticModel: SemanticModel) => {
+  const documentSymbols: DocumentSymbol[] = []
+
+  for (const statement of semanticModel.statements) {
+    if (statement.type !== "let") continue
+
+    const { name, range } = statement
+    documentSymbols.push({
+      name: name.value,
+      kind: SymbolKind.Variable,
+      range,
+      selectionRange: name.range,
+    })
+  }
+
+  return documentSymbols
+}
+
+export const testCollectDocumentSymbols = () => {
+  const table = [
+    {
+      source: "let x be 1\n  let y be x\nlet x be y",
+      expected: [
+        ["x", [[0, 0], [0, 10]], [[0, 4], [0, 5]]],
+        ["y", [[1, 2], [1, 12]], [[1, 6], [1, 7]]],
+        ["x", [[2, 0], [2, 10]], [[2, 4], [2, 5]]],
+      ],
+    },
+    // Error statements aren't symbols.
+    {
+      source: "let x = 1\nlet y be 2",
+      expected: [
+        ["y", [[1, 0], [1, 10]], [[1, 4], [1, 5]]],
+      ],
+    },
+  ]
+
+  for (const { source, expected } of table) {
+    const documentSymbols = collectDocumentSymbols(analyzeSource(source))
+    const actual = documentSymbols.map(s => [
+      s.name,
+      rangeToMatrix(s.range),
+      rangeToMatrix(s.selectionRange),
+    ])
+    assert.deepStrictEqual(actual, expected)
+  }
+}
+
 /** Evaluate the program and print the last variable. */
 const evaluate = (statements: Statement[]) => {
   /** Map from variable names to values. */
@@ -828,6 +1276,60 @@ const createHighlights = (uri: string, position: Position) => {
   return highlights
 }
 
+/**
+ * Create completion items for the specified position.
+ */
+const createCompletionItems = (uri: string, position: Position) => {
+  const openDocument = openDocuments.get(uri)
+  if (!openDocument) {
+    return
+  }
+
+  return collectCompletionItems(openDocument.semanticModel, position)
+}
+
+/**
+ * Create semantic tokens of the whole document.
+ */
+const createSemanticTokens = (uri: string): SemanticTokens | undefined => {
+  const openDocument = openDocuments.get(uri)
+  if (!openDocument) {
+    return
+  }
+
+  const data = encodeSemanticTokens(openDocument.semanticModel)
+  return { data }
+}
+
+/**
+ * Create symbols for outline of the document.
+ */
+const createDocumentSymbols = (uri: string) => {
+  const openDocument = openDocuments.get(uri)
+  if (!openDocument) {
+    return
+  }
+
+  return collectDocumentSymbols(openDocument.semanticModel)
+}
+
+/**
+ * Find the definition-site of the symbol at the specified position.
+ */
+const createDefinitionLocation = (uri: string, position: Position): Location | undefined => {
+  const openDocument = openDocuments.get(uri)
+  if (!openDocument) {
+    return
+  }
+
+  const range = findDefinitionRange(openDocument.semanticModel, position)
+  if (!range) {
+    return
+  }
+
+  return { uri, range }
+}
+
 /**
  * Prepare for symbol renaming.
  *
@@ -857,26 +1359,11 @@ const createRenameEdit = (uri: string, position: Position, newName: string): Wor
     return
   }
 
-  const hit = hitTestSymbol(openDocument.semanticModel, position)
-  if (!hit) {
+  const edits = collectRenameEdits(openDocument.semanticModel, position, newName)
+  if (edits.length === 0) {
     return
   }
 
-  const edits: TextEdit[] = []
-  const { definition, references } = hit.symbolDefinition
-
-  edits.push({
-    range: definition.range,
-    newText: newName,
-  })
-
-  for (const r of references) {
-    edits.push({
-      range: r.range,
-      newText: newName,
-    })
-  }
-
   const documentChanges: TextDocumentEdit[] = [
     { textDocument: { uri, version: openDocument.version }, edits }
   ]
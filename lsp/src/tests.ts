@@ -6,6 +6,11 @@ import {
   testParseTokens,
   testAnalyzeStatements,
   testHitTestSymbol,
+  testFindDefinitionRange,
+  testCollectRenameEdits,
+  testCollectCompletionItems,
+  testEncodeSemanticTokens,
+  testCollectDocumentSymbols,
   testEvaluate,
 } from "./curage-server"
 
@@ -15,6 +20,11 @@ testTokenize()
 testParseTokens()
 testAnalyzeStatements()
 testHitTestSymbol()
+testFindDefinitionRange()
+testCollectRenameEdits()
+testCollectCompletionItems()
+testEncodeSemanticTokens()
+testCollectDocumentSymbols()
 testEvaluate()
 
 console.log("Success!")